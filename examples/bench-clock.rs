@@ -1,13 +1,77 @@
-use cover_circuit::{index_secret, Clock};
+use std::time::{Duration, Instant};
+
+use cover_circuit::{
+    cache::{load_or_genesis, Source},
+    index_secret, public_key, Clock,
+};
 use plonky2::plonk::circuit_data::CircuitConfig;
 use plonky2_maybe_rayon::rayon;
 use rand::{seq::SliceRandom, thread_rng, Rng};
 use tracing::info;
 
+// usage: bench-clock [--size N] [--updates N] [--arity N] [--threads N] [--no-zk]
+//                    [--format csv|json] [--cache-dir DIR]
+// the per-operation times are printed to stdout, logs go to stderr
+struct Args {
+    size: usize,
+    num_update: usize,
+    arity: usize,
+    num_thread: Option<usize>,
+    zero_knowledge: bool,
+    json: bool,
+    cache_dir: Option<String>,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = Args {
+        size: 1 << 10,
+        num_update: 10,
+        arity: 2,
+        num_thread: None,
+        zero_knowledge: true,
+        json: false,
+        cache_dir: None,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .ok_or(anyhow::anyhow!("missing value for {arg}"))
+        };
+        match &*arg {
+            "--size" => args.size = value()?.parse()?,
+            "--updates" => args.num_update = value()?.parse()?,
+            "--arity" => args.arity = value()?.parse()?,
+            "--threads" => args.num_thread = Some(value()?.parse()?),
+            "--no-zk" => args.zero_knowledge = false,
+            "--format" => {
+                args.json = match &*value()? {
+                    "csv" => false,
+                    "json" => true,
+                    format => anyhow::bail!("unknown format {format}"),
+                }
+            }
+            "--cache-dir" => args.cache_dir = Some(value()?),
+            _ => anyhow::bail!("unknown argument {arg}"),
+        }
+    }
+    anyhow::ensure!(args.size > 0, "clock size must be positive");
+    anyhow::ensure!(args.arity > 0, "merge arity must be positive");
+    Ok(args)
+}
+
 fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+    let args = parse_args()?;
+    if let Some(num_thread) = args.num_thread {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(num_thread)
+            .build_global()?
+    }
     let mut config = CircuitConfig::standard_ecc_config();
-    config.zero_knowledge = true;
+    config.zero_knowledge = args.zero_knowledge;
 
     let num_thread = rayon::current_num_threads();
     info!(
@@ -16,36 +80,75 @@ fn main() -> anyhow::Result<()> {
         std::thread::available_parallelism(),
     );
 
-    const S: usize = 1 << 10;
-    let (clock, circuit) = Clock::<S>::genesis(
-        [(); S].map({
-            let mut i = 0;
-            move |()| {
-                let secret = index_secret(i);
-                i += 1;
-                cover_circuit::public_key(secret)
-            }
-        }),
-        config,
-    )?;
+    let mut records = Vec::<(&str, Duration)>::new();
+    let keys = (0..args.size)
+        .map(|i| public_key(index_secret(i)))
+        .collect::<Vec<_>>();
+    let start = Instant::now();
+    let (clock, circuit, source) = if let Some(cache_dir) = &args.cache_dir {
+        load_or_genesis(cache_dir, &keys, config)?
+    } else {
+        let (clock, circuit) = Clock::genesis(&keys, config)?;
+        (clock, circuit, Source::Generated)
+    };
+    let op = match source {
+        Source::Loaded => "load",
+        Source::Generated => "genesis",
+    };
+    records.push((op, start.elapsed()));
     clock.verify(&circuit)?;
 
-    // let clock_bytes =
-    //     std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("genesis_clock4.bin"))?;
-    // let circuit_bytes = std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("circuit4.bin"))?;
-    // let (clock, circuit) = Clock::<S>::from_bytes(clock_bytes, &circuit_bytes, config)?;
-
     let mut clocks = vec![clock];
-    for _ in 0..10 {
+    for _ in 0..args.num_update {
         let clock1 = clocks.choose(&mut rand::thread_rng()).unwrap();
         let clock2 = clocks.choose(&mut rand::thread_rng()).unwrap();
-        let index = thread_rng().gen_range(0..S);
+        let index = thread_rng().gen_range(0..args.size);
         info!("updating {index} with {clock1:?} and {clock2:?}");
-        // let start = Instant::now();
+        let start = Instant::now();
         let clock = clock1.update(index, index_secret(index), clock2, &circuit)?;
+        records.push(("update", start.elapsed()));
         info!("updated into {clock:?}");
+        let start = Instant::now();
         clock.verify(&circuit)?;
+        records.push(("verify", start.elapsed()));
         clocks.push(clock)
     }
+
+    if args.arity > 1 {
+        let inputs = (0..args.arity)
+            .map(|_| clocks.choose(&mut rand::thread_rng()).unwrap().clone())
+            .collect::<Vec<_>>();
+        let start = Instant::now();
+        let clock = Clock::merge_all(&inputs, &circuit)?;
+        records.push(("merge", start.elapsed()));
+        info!("merged into {clock:?}");
+    }
+
+    if args.json {
+        let records = records
+            .iter()
+            .map(|(op, duration)| {
+                format!(
+                    r#"{{"op":"{op}","size":{},"arity":{},"zk":{},"micros":{}}}"#,
+                    args.size,
+                    args.arity,
+                    args.zero_knowledge,
+                    duration.as_micros()
+                )
+            })
+            .collect::<Vec<_>>();
+        println!("[{}]", records.join(","))
+    } else {
+        println!("op,size,arity,zk,micros");
+        for (op, duration) in records {
+            println!(
+                "{op},{},{},{},{}",
+                args.size,
+                args.arity,
+                args.zero_knowledge,
+                duration.as_micros()
+            )
+        }
+    }
     Ok(())
 }
@@ -5,25 +5,19 @@ use plonky2::plonk::circuit_data::CircuitConfig;
 
 fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
-    // let (clock, circuit) = Clock::genesis(
-    let (clock, _circuit) = Clock::genesis(
-        [(); 4].map({
-            let mut i = 0;
-            move |()| {
-                let secret = index_secret(i);
-                i += 1;
-                public_key(secret)
-            }
-        }),
+    let (clock, circuit) = Clock::genesis(
+        &(0..4)
+            .map(|i| public_key(index_secret(i)))
+            .collect::<Vec<_>>(),
         CircuitConfig::standard_ecc_config(),
     )?;
     write(
         Path::new(env!("CARGO_MANIFEST_DIR")).join("genesis_clock4.bin"),
         clock.to_bytes(),
     )?;
-    // write(
-    //     Path::new(env!("CARGO_MANIFEST_DIR")).join("circuit4.bin"),
-    //     circuit.to_bytes()?,
-    // )?;
+    write(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("circuit4.bin"),
+        circuit.to_bytes()?,
+    )?;
     Ok(())
 }
@@ -0,0 +1,185 @@
+use std::{
+    fs::{create_dir_all, read, rename, write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering::SeqCst},
+};
+
+use plonky2::{
+    field::types::PrimeField64, fri::reduction_strategies::FriReductionStrategy,
+    hash::hash_types::HashOut, plonk::circuit_data::CircuitConfig,
+};
+use tracing::{info, warn};
+
+use crate::{ser::checksum, Clock, ClockCircuit, F};
+
+// where the artifacts returned by `load_or_genesis` come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Loaded,
+    Generated,
+}
+
+// genesis takes minutes for large clocks, so the artifacts are persisted under `dir`, keyed by the
+// clock size and a digest of everything genesis depends on i.e. the keys and the config. cached
+// artifacts are validated on loading (checksum of the circuit, proof of the clock) and regenerated
+// on any failure
+pub fn load_or_genesis(
+    dir: impl AsRef<Path>,
+    keys: &[HashOut<F>],
+    config: CircuitConfig,
+) -> anyhow::Result<(Clock, ClockCircuit, Source)> {
+    let dir = dir.as_ref();
+    let (circuit_path, clock_path) = paths(dir, keys, &config);
+
+    if circuit_path.exists() && clock_path.exists() {
+        let load = || {
+            let circuit = ClockCircuit::from_bytes(&read(&circuit_path)?, config.clone())?;
+            let clock = Clock::from_bytes(&read(&clock_path)?, &circuit)?;
+            anyhow::Ok((clock, circuit))
+        };
+        match load() {
+            Ok((clock, circuit)) => {
+                info!("loaded cached genesis from {}", dir.display());
+                return Ok((clock, circuit, Source::Loaded));
+            }
+            Err(err) => warn!("invalid cached genesis in {}: {err}", dir.display()),
+        }
+    }
+
+    let (clock, circuit) = Clock::genesis(keys, config)?;
+    create_dir_all(dir)?;
+    // the clock goes last, because loading is only attempted when both files exist
+    write_atomic(&circuit_path, &circuit.to_bytes()?)?;
+    write_atomic(&clock_path, &clock.to_bytes())?;
+    info!("cached genesis into {}", dir.display());
+    Ok((clock, circuit, Source::Generated))
+}
+
+// binaries may start from the same cache dir at the same time, so an artifact is written to a
+// temporary file in the same dir and renamed into place, and a concurrent reader never sees it
+// partially written
+fn write_atomic(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    static NUM_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let tmp_path = path.with_extension(format!(
+        "tmp-{}-{}",
+        std::process::id(),
+        NUM_WRITE.fetch_add(1, SeqCst)
+    ));
+    write(&tmp_path, bytes)?;
+    rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn paths(dir: &Path, keys: &[HashOut<F>], config: &CircuitConfig) -> (PathBuf, PathBuf) {
+    let digest = digest(keys, config);
+    (
+        dir.join(format!("circuit-{}-{digest}.bin", keys.len())),
+        dir.join(format!("genesis-{}-{digest}.bin", keys.len())),
+    )
+}
+
+// the config fields are listed one by one instead of going through the `Debug` output, whose
+// format is not meant to be stable
+fn digest(keys: &[HashOut<F>], config: &CircuitConfig) -> String {
+    let fri_config = &config.fri_config;
+    let mut values = vec![
+        config.num_wires,
+        config.num_routed_wires,
+        config.num_constants,
+        config.use_base_arithmetic_gate as _,
+        config.security_bits,
+        config.num_challenges,
+        config.zero_knowledge as _,
+        config.max_quotient_degree_factor,
+        fri_config.rate_bits,
+        fri_config.cap_height,
+        fri_config.proof_of_work_bits as _,
+        fri_config.num_query_rounds,
+    ];
+    match &fri_config.reduction_strategy {
+        FriReductionStrategy::Fixed(arity_bits) => {
+            values.extend([0, arity_bits.len()]);
+            values.extend(arity_bits)
+        }
+        FriReductionStrategy::ConstantArityBits(arity_bits, final_poly_bits) => {
+            values.extend([1, *arity_bits, *final_poly_bits])
+        }
+        FriReductionStrategy::MinSize(max_arity_bits) => {
+            values.extend([2, max_arity_bits.map_or(0, |bits| bits + 1)])
+        }
+    }
+    let mut bytes = values
+        .into_iter()
+        .flat_map(|value| (value as u64).to_le_bytes())
+        .collect::<Vec<_>>();
+    for key in keys {
+        for element in key.elements {
+            bytes.extend_from_slice(&element.to_canonical_u64().to_le_bytes())
+        }
+    }
+    checksum(&bytes)
+        .iter()
+        .take(8)
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_dir, remove_dir_all};
+
+    use crate::{index_secret, public_key};
+
+    use super::*;
+
+    fn keys(offset: usize) -> Vec<HashOut<F>> {
+        (0..4)
+            .map(|i| public_key(index_secret(offset + i)))
+            .collect()
+    }
+
+    #[test]
+    fn cache_key() {
+        let dir = Path::new("cache");
+        let config = CircuitConfig::standard_ecc_config();
+        let paths0 = paths(dir, &keys(0), &config);
+        assert_eq!(paths(dir, &keys(0), &config), paths0);
+        assert_ne!(paths(dir, &keys(4), &config), paths0);
+        let mut zk_config = config.clone();
+        zk_config.zero_knowledge = true;
+        assert_ne!(paths(dir, &keys(0), &zk_config), paths0);
+        let mut fri_config = config.clone();
+        fri_config.fri_config.num_query_rounds += 1;
+        assert_ne!(paths(dir, &keys(0), &fri_config), paths0);
+    }
+
+    #[test]
+    fn load_or_regenerate() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("cover-circuit-cache-{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        let config = CircuitConfig::standard_ecc_config();
+
+        let (_, _, source) = load_or_genesis(&dir, &keys(0), config.clone())?;
+        assert_eq!(source, Source::Generated);
+        let (genesis, circuit, source) = load_or_genesis(&dir, &keys(0), config.clone())?;
+        assert_eq!(source, Source::Loaded);
+        let clock = genesis.update(0, index_secret(0), &genesis, &circuit)?;
+        clock.verify(&circuit)?;
+        assert_eq!(clock.counters().collect::<Vec<_>>(), [1, 0, 0, 0]);
+
+        let (circuit_path, _) = paths(&dir, &keys(0), &config);
+        let mut bytes = read(&circuit_path)?;
+        let len = bytes.len();
+        bytes[len / 2] ^= 1;
+        write(&circuit_path, bytes)?;
+        let (_, _, source) = load_or_genesis(&dir, &keys(0), config.clone())?;
+        assert_eq!(source, Source::Generated);
+        let (_, _, source) = load_or_genesis(&dir, &keys(0), config)?;
+        assert_eq!(source, Source::Loaded);
+        // no temporary file is left behind
+        assert_eq!(read_dir(&dir)?.count(), 2);
+
+        remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
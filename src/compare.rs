@@ -0,0 +1,218 @@
+use std::cmp::Ordering;
+
+use plonky2::{
+    field::types::Field,
+    hash::{
+        hash_types::HashOut,
+        hashing::hash_n_to_hash_no_pad,
+        poseidon::{PoseidonHash, PoseidonPermutation},
+    },
+    iop::{
+        target::Target,
+        witness::{PartialWitness, WitnessWrite},
+    },
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData, VerifierCircuitData},
+        proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget},
+        prover::prove,
+    },
+    util::timing::TimingTree,
+};
+use plonky2_u32::gadgets::{arithmetic_u32::U32Target, multiple_comparison::list_le_u32_circuit};
+
+use crate::{verifier_data_targets, Clock, ClockVerifier, C, D, F};
+
+// a clock is identified by a blinded commitment to its counters, because the counters alone are
+// small enough to be brute forced out of their hash
+pub type Blinding = [F; 4];
+
+impl Clock {
+    pub fn commitment(&self, blinding: Blinding) -> HashOut<F> {
+        let inputs = self
+            .counters()
+            .map(F::from_canonical_u32)
+            .chain(blinding)
+            .collect::<Vec<_>>();
+        hash_n_to_hash_no_pad::<_, PoseidonPermutation<_>>(&inputs)
+    }
+}
+
+// proves the causal relation of two clocks without revealing their counters. the public inputs are
+// the commitments of both clocks, followed by whether the first clock is less than or equal to the
+// second one, and whether it is greater than or equal to it
+#[derive(Debug)]
+pub struct CompareCircuit {
+    pub data: CircuitData<F, C, D>,
+    targets: CompareCircuitTargets,
+}
+
+#[derive(Debug)]
+struct CompareCircuitTargets {
+    proof1: ProofWithPublicInputsTarget<D>,
+    blinding1: Vec<Target>,
+    proof2: ProofWithPublicInputsTarget<D>,
+    blinding2: Vec<Target>,
+}
+
+// the only thing a party that receives comparisons needs
+#[derive(Debug)]
+pub struct CompareVerifier {
+    pub data: VerifierCircuitData<F, C, D>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub proof: ProofWithPublicInputs<F, C, D>,
+}
+
+impl CompareCircuit {
+    // the counters are only hidden by a zero knowledge proof, so the config is forced to be one
+    pub fn new(verifier: &ClockVerifier, mut config: CircuitConfig) -> Self {
+        config.zero_knowledge = true;
+        let mut builder = CircuitBuilder::<F, D>::new(config);
+
+        // the verifier data is a constant, and the exposed verifier data is checked against it as
+        // `Clock::verify` does, so the compared proofs cannot come from any other circuit
+        let common = &verifier.data.common;
+        let verifier_data = builder.constant_verifier_data(&verifier.data.verifier_only);
+        let proof1 = builder.add_virtual_proof_with_pis(common);
+        builder.verify_proof::<C>(&proof1, &verifier_data, common);
+        let proof2 = builder.add_virtual_proof_with_pis(common);
+        builder.verify_proof::<C>(&proof2, &verifier_data, common);
+        let blinding1 = builder.add_virtual_targets(4);
+        let blinding2 = builder.add_virtual_targets(4);
+
+        let size = verifier.size();
+        let verifier_data = verifier_data_targets(&verifier_data);
+        for proof in [&proof1, &proof2] {
+            for (target, verifier_target) in proof.public_inputs[size..].iter().zip(&verifier_data)
+            {
+                builder.connect(*target, *verifier_target)
+            }
+        }
+        let counters1 = &proof1.public_inputs[..size];
+        let counters2 = &proof2.public_inputs[..size];
+
+        let commitment1 =
+            builder.hash_n_to_hash_no_pad::<PoseidonHash>([counters1, &blinding1[..]].concat());
+        let commitment2 =
+            builder.hash_n_to_hash_no_pad::<PoseidonHash>([counters2, &blinding2[..]].concat());
+
+        // the counters are range checked by the clock circuit already
+        let mut le = builder._true();
+        let mut ge = builder._true();
+        for (counter1, counter2) in counters1.iter().zip(counters2) {
+            let x1 = U32Target(*counter1);
+            let x2 = U32Target(*counter2);
+            let x_le = list_le_u32_circuit(&mut builder, vec![x1], vec![x2]);
+            le = builder.and(le, x_le);
+            let x_ge = list_le_u32_circuit(&mut builder, vec![x2], vec![x1]);
+            ge = builder.and(ge, x_ge);
+        }
+
+        builder.register_public_inputs(&commitment1.elements);
+        builder.register_public_inputs(&commitment2.elements);
+        builder.register_public_input(le.target);
+        builder.register_public_input(ge.target);
+        Self {
+            data: builder.build(),
+            targets: CompareCircuitTargets {
+                proof1,
+                blinding1,
+                proof2,
+                blinding2,
+            },
+        }
+    }
+
+    pub fn prove(
+        &self,
+        clock1: &Clock,
+        blinding1: Blinding,
+        clock2: &Clock,
+        blinding2: Blinding,
+    ) -> anyhow::Result<Comparison> {
+        let mut pw = PartialWitness::new();
+        pw.set_proof_with_pis_target(&self.targets.proof1, &clock1.proof);
+        pw.set_proof_with_pis_target(&self.targets.proof2, &clock2.proof);
+        for (target, value) in self.targets.blinding1.iter().zip(blinding1) {
+            pw.set_target(*target, value)
+        }
+        for (target, value) in self.targets.blinding2.iter().zip(blinding2) {
+            pw.set_target(*target, value)
+        }
+
+        let mut timing =
+            TimingTree::new("prove compare", "INFO".parse().map_err(anyhow::Error::msg)?);
+        let proof = prove(&self.data.prover_only, &self.data.common, pw, &mut timing)?;
+        timing.print();
+
+        let comparison = Comparison { proof };
+        anyhow::ensure!(
+            comparison.commitments()?
+                == (clock1.commitment(blinding1), clock2.commitment(blinding2)),
+            "commitments mismatch"
+        );
+        anyhow::ensure!(
+            comparison.ordering()? == clock1.partial_cmp(clock2),
+            "ordering mismatch"
+        );
+        Ok(comparison)
+    }
+
+    pub fn verify(&self, comparison: &Comparison) -> anyhow::Result<()> {
+        self.data
+            .verify(comparison.proof.clone())
+            .map_err(Into::into)
+    }
+
+    pub fn verifier(&self) -> CompareVerifier {
+        CompareVerifier {
+            data: self.data.verifier_data(),
+        }
+    }
+}
+
+impl CompareVerifier {
+    pub fn verify(&self, comparison: &Comparison) -> anyhow::Result<()> {
+        self.data
+            .verify(comparison.proof.clone())
+            .map_err(Into::into)
+    }
+}
+
+// the public inputs of a comparison proof, see `CompareCircuit`
+pub(crate) const NUM_PUBLIC_INPUTS: usize = 10;
+
+impl Comparison {
+    pub fn commitments(&self) -> anyhow::Result<(HashOut<F>, HashOut<F>)> {
+        let public_inputs = self.public_inputs()?;
+        Ok((
+            HashOut::from_partial(&public_inputs[..4]),
+            HashOut::from_partial(&public_inputs[4..8]),
+        ))
+    }
+
+    pub fn ordering(&self) -> anyhow::Result<Option<Ordering>> {
+        let public_inputs = self.public_inputs()?;
+        Ok(
+            match (public_inputs[8] == F::ONE, public_inputs[9] == F::ONE) {
+                (true, true) => Some(Ordering::Equal),
+                (true, false) => Some(Ordering::Less),
+                (false, true) => Some(Ordering::Greater),
+                (false, false) => None,
+            },
+        )
+    }
+
+    // the proof is public so it may come from anywhere, and it is not necessarily verified yet
+    fn public_inputs(&self) -> anyhow::Result<&[F]> {
+        anyhow::ensure!(
+            self.proof.public_inputs.len() == NUM_PUBLIC_INPUTS,
+            "expect {NUM_PUBLIC_INPUTS} public inputs, got {}",
+            self.proof.public_inputs.len()
+        );
+        Ok(&self.proof.public_inputs)
+    }
+}
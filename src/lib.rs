@@ -1,27 +1,35 @@
+pub mod cache;
+pub mod compare;
 pub mod ser;
+pub mod shrink;
 
-use std::{collections::HashMap, fmt::Debug};
+use std::{cmp::Ordering, collections::HashMap, fmt::Debug};
 
 use plonky2::{
     field::types::{Field, PrimeField64},
+    gates::noop::NoopGate,
     hash::{
-        hash_types::{HashOut, HashOutTarget},
+        hash_types::{HashOut, HashOutTarget, MerkleCapTarget},
         hashing::hash_n_to_hash_no_pad,
         poseidon::{PoseidonHash, PoseidonPermutation},
     },
     iop::{
-        target::Target,
+        target::{BoolTarget, Target},
         witness::{PartialWitness, WitnessWrite},
     },
     plonk::{
         circuit_builder::CircuitBuilder,
-        circuit_data::{CircuitConfig, CircuitData, VerifierCircuitTarget},
+        circuit_data::{
+            CircuitConfig, CircuitData, CommonCircuitData, VerifierCircuitData,
+            VerifierCircuitTarget, VerifierOnlyCircuitData,
+        },
         config::{GenericConfig, PoseidonGoldilocksConfig},
         proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget},
         prover::prove,
     },
     util::timing::TimingTree,
 };
+use plonky2_maybe_rayon::*;
 use plonky2_u32::gadgets::{
     arithmetic_u32::U32Target, multiple_comparison::list_le_u32_circuit,
     range_check::range_check_u32_circuit,
@@ -32,11 +40,11 @@ pub type C = PoseidonGoldilocksConfig;
 pub type F = <C as GenericConfig<D>>::F;
 
 #[derive(Clone)]
-pub struct Clock<const S: usize> {
+pub struct Clock {
     pub proof: ProofWithPublicInputs<F, C, D>,
 }
 
-impl<const S: usize> Debug for Clock<S> {
+impl Debug for Clock {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let counters = self
             .counters()
@@ -47,30 +55,151 @@ impl<const S: usize> Debug for Clock<S> {
     }
 }
 
-impl<const S: usize> Clock<S> {
+// the causal partial order of vector clocks. the proofs are not compared, so clocks with the same
+// counters are equal no matter how they are proved. clocks of different sizes are never related
+impl PartialEq for Clock {
+    fn eq(&self, other: &Self) -> bool {
+        self.counters().eq(other.counters())
+    }
+}
+
+impl PartialOrd for Clock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.size() != other.size() {
+            return None;
+        }
+        self.counters().zip(other.counters()).try_fold(
+            Ordering::Equal,
+            |ordering, (counter, other_counter)| match (ordering, counter.cmp(&other_counter)) {
+                (ordering, Ordering::Equal) | (Ordering::Equal, ordering) => Some(ordering),
+                (ordering, other_ordering) if ordering == other_ordering => Some(ordering),
+                _ => None,
+            },
+        )
+    }
+}
+
+impl Clock {
     pub fn counters(&self) -> impl Iterator<Item = u32> + '_ {
-        self.proof
-            .public_inputs
+        self.proof.public_inputs[..self.size()]
             .iter()
-            .take(S)
             .map(|counter| counter.to_canonical_u64() as _)
     }
+
+    // a total order that extends the causal one, for breaking ties between concurrent clocks
+    // deterministically. a clock that happens before another one is not greater at any counter,
+    // so the lexicographic order of the counters already agrees with the causal order
+    pub fn arbitrary_cmp(&self, other: &Self) -> Ordering {
+        self.counters().cmp(other.counters())
+    }
+
+    // the public inputs are the counters followed by the verifier data of the clock circuit, so the
+    // clock size is carried by the proof itself. the verifier data takes a digest and a cap, which
+    // has as many hashes as the other caps of the proof
+    pub fn size(&self) -> usize {
+        let cap_len = self.proof.proof.wires_cap.0.len();
+        self.proof
+            .public_inputs
+            .len()
+            .saturating_sub(4 * (1 + cap_len))
+    }
+
+    // the clock circuit only binds the inner proofs to the verifier data exposed by the outer one,
+    // so every verification must check that it is the verifier data of the clock circuit
+    fn check_verifier_data(
+        &self,
+        verifier_only: &VerifierOnlyCircuitData<C, D>,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.proof.public_inputs[self.size()..] == verifier_data_elements(verifier_only)[..],
+            "clock is not proved by the expected circuit"
+        );
+        Ok(())
+    }
+}
+
+pub(crate) fn num_counters(common: &CommonCircuitData<F, D>) -> usize {
+    common
+        .num_public_inputs
+        .saturating_sub(4 * (1 + common.config.fri_config.num_cap_elements()))
+}
+
+// the verifier data is exposed as the circuit digest followed by the constants sigmas cap
+pub(crate) fn verifier_data_elements(verifier_only: &VerifierOnlyCircuitData<C, D>) -> Vec<F> {
+    verifier_only
+        .circuit_digest
+        .elements
+        .into_iter()
+        .chain(
+            verifier_only
+                .constants_sigmas_cap
+                .0
+                .iter()
+                .flat_map(|hash| hash.elements),
+        )
+        .collect()
+}
+
+pub(crate) fn verifier_data_targets(verifier_data: &VerifierCircuitTarget) -> Vec<Target> {
+    verifier_data
+        .circuit_digest
+        .elements
+        .into_iter()
+        .chain(
+            verifier_data
+                .constants_sigmas_cap
+                .0
+                .iter()
+                .flat_map(|hash| hash.elements),
+        )
+        .collect()
+}
+
+fn select_verifier_data(
+    builder: &mut CircuitBuilder<F, D>,
+    b: BoolTarget,
+    x: &VerifierCircuitTarget,
+    y: &VerifierCircuitTarget,
+) -> VerifierCircuitTarget {
+    let mut select_hash = |x: &HashOutTarget, y: &HashOutTarget| {
+        HashOutTarget::from_vec(
+            x.elements
+                .iter()
+                .zip(&y.elements)
+                .map(|(x, y)| builder.select(b, *x, *y))
+                .collect(),
+        )
+    };
+    VerifierCircuitTarget {
+        constants_sigmas_cap: MerkleCapTarget(
+            x.constants_sigmas_cap
+                .0
+                .iter()
+                .zip(&y.constants_sigmas_cap.0)
+                .map(|(x, y)| select_hash(x, y))
+                .collect(),
+        ),
+        circuit_digest: select_hash(&x.circuit_digest, &y.circuit_digest),
+    }
 }
 
 #[derive(Debug)]
-pub struct ClockCircuit<const S: usize> {
+pub struct ClockCircuit {
     pub data: CircuitData<F, C, D>,
-    targets: Option<ClockCircuitTargets<S>>,
+    targets: ClockCircuitTargets,
 }
 
 #[derive(Debug)]
-struct ClockCircuitTargets<const S: usize> {
-    // the only public input is the output clock, which is not expected to be set before proving
-    // every target is witness
+struct ClockCircuitTargets {
+    // the public inputs are the output clock and `verifier_data`, which are not expected to be set
+    // before proving. every target is witness
 
     // common inputs
     proof1: ProofWithPublicInputsTarget<D>,
-    verifier_data1: VerifierCircuitTarget,
+    // the verifier data of the clock circuit itself, see `ClockCircuit::new`
+    verifier_data: VerifierCircuitTarget,
+    // when proving genesis, the inner proofs come from the base circuit and count as zero clocks
+    is_base: BoolTarget,
 
     // increment inputs, when merging...
     updated_index: Target,   // ...2^32
@@ -80,47 +209,92 @@ struct ClockCircuitTargets<const S: usize> {
     // enable2: BoolTarget,
     // merge inputs, when incrementing...
     proof2: ProofWithPublicInputsTarget<D>, // ...same to `proof1`
-    verifier_data2: VerifierCircuitTarget,  // ...same to `verifier_data1`
 }
 
-impl<const S: usize> ClockCircuit<S> {
-    pub fn new_genesis(config: CircuitConfig) -> Self {
-        let mut builder = CircuitBuilder::<F, D>::new(config);
-        let output_counters = builder.constants(&[F::ZERO; S]);
-        builder.register_public_inputs(&output_counters);
-        Self {
-            data: builder.build(),
-            targets: None,
+const MAX_BUILD_ROUND: usize = 8;
+
+impl ClockCircuit {
+    // the clock circuit verifies proofs of itself, so it must be built against its own common data,
+    // which is found by rebuilding against the common data of the last build until it stops
+    // changing. the first build is against a circuit with nothing but the public inputs, and until
+    // the last build any verifier data of the right shape serves as the base one
+    fn build(
+        keys: &[HashOut<F>],
+        dummy_key: HashOut<F>,
+        config: CircuitConfig,
+    ) -> anyhow::Result<(Self, BaseCircuit)> {
+        let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+        for _ in 0..keys.len() + 4 * (1 + config.fri_config.num_cap_elements()) {
+            builder.add_virtual_public_input();
+        }
+        let mut inner = builder.build::<C>();
+        for _ in 0..MAX_BUILD_ROUND {
+            let circuit = Self::new(
+                &inner.common,
+                keys,
+                dummy_key,
+                &inner.verifier_only,
+                config.clone(),
+            );
+            if circuit.data.common == inner.common {
+                let base = BaseCircuit::new(&inner.common)?;
+                let circuit = Self::new(
+                    &inner.common,
+                    keys,
+                    dummy_key,
+                    &base.data.verifier_only,
+                    config,
+                );
+                anyhow::ensure!(
+                    circuit.data.common == inner.common,
+                    "clock circuit changes shape with the base verifier data"
+                );
+                return Ok((circuit, base));
+            }
+            inner = circuit.data;
         }
+        anyhow::bail!("clock circuit does not converge in {MAX_BUILD_ROUND} builds")
     }
 
-    pub fn new(
-        inner: &Self,
-        keys: &[HashOut<F>; S],
+    fn new(
+        inner_common: &CommonCircuitData<F, D>,
+        keys: &[HashOut<F>],
         dummy_key: HashOut<F>,
+        base: &VerifierOnlyCircuitData<C, D>,
         config: CircuitConfig,
     ) -> Self {
+        assert_eq!(num_counters(inner_common), keys.len());
         let mut builder = CircuitBuilder::<F, D>::new(config);
 
-        let proof1 = builder.add_virtual_proof_with_pis(&inner.data.common);
-        // the slicing is not necessary for now, just in case we add more public inputs later
-        let input_counters1 = &proof1.public_inputs[..S];
-        let proof2 = builder.add_virtual_proof_with_pis(&inner.data.common);
-        let input_counters2 = &proof2.public_inputs[..S];
-
-        let updated_index = builder.add_virtual_target();
-        let updated_counter = builder.add_virtual_target();
-        // although there is `add_virtual_nonnative_target`, but seems like currently it can only be
-        // internally used by generators. there's no `set_nonnative_target` companion for witness
-        // let num_limbs = CircuitBuilder::<F, D>::num_nonnative_limbs::<Secp256K1Scalar>();
-        let sig = builder.add_virtual_target();
-
-        let verifier_data1 =
-            builder.add_virtual_verifier_data(inner.data.common.config.fri_config.cap_height);
-        builder.verify_proof::<C>(&proof1, &verifier_data1, &inner.data.common);
-        let verifier_data2 =
-            builder.add_virtual_verifier_data(inner.data.common.config.fri_config.cap_height);
-        builder.verify_proof::<C>(&proof2, &verifier_data2, &inner.data.common);
+        // every witness target is allocated before any gadget is added, so a deserialized circuit
+        // can reconstruct the same targets with `ClockCircuitTargets::new`
+        let targets = ClockCircuitTargets::new(&mut builder, inner_common);
+        builder.assert_bool(targets.is_base);
+        let input_counters1 = &targets.proof1.public_inputs[..keys.len()];
+        let input_counters2 = &targets.proof2.public_inputs[..keys.len()];
+        let updated_index = targets.updated_index;
+        let updated_counter = targets.updated_counter;
+        let sig = targets.sig;
+
+        // cyclic recursion in the way of plonky2's `conditionally_verify_cyclic_proof`: the inner
+        // proofs are verified against the verifier data exposed by this proof, and must expose the
+        // same verifier data themselves. once the verifier checks the outermost exposed verifier
+        // data, every proof down to genesis is bound to this circuit instead of some circuit built
+        // with other keys. the only exception is the base proofs of genesis, which are verified
+        // against the constant base verifier data, and whose counters are ignored
+        let verifier_data = verifier_data_targets(&targets.verifier_data);
+        let base = builder.constant_verifier_data(base);
+        let inner_verifier_data =
+            select_verifier_data(&mut builder, targets.is_base, &base, &targets.verifier_data);
+        for proof in [&targets.proof1, &targets.proof2] {
+            builder.verify_proof::<C>(proof, &inner_verifier_data, inner_common);
+            for (inner_target, target) in
+                proof.public_inputs[keys.len()..].iter().zip(&verifier_data)
+            {
+                let inner_target = builder.select(targets.is_base, *target, *inner_target);
+                builder.connect(inner_target, *target)
+            }
+        }
         // let enable2 = builder.add_virtual_bool_target_safe();
         // builder.conditionally_verify_proof_or_dummy::<C>(
         //     enable2,
@@ -129,6 +303,7 @@ impl<const S: usize> ClockCircuit<S> {
         //     &inner.data.common,
         // )?;
 
+        let zero = builder.zero();
         let mut updated_key = builder.constant_hash(dummy_key);
 
         let output_counters = input_counters1
@@ -139,7 +314,8 @@ impl<const S: usize> ClockCircuit<S> {
                 let key = keys[i];
                 let i = builder.constant(F::from_canonical_usize(i));
                 let is_updated = builder.is_equal(updated_index, i);
-                let x1 = U32Target(builder.select(is_updated, updated_counter, *input_counter1));
+                let input_counter1 = builder.select(targets.is_base, zero, *input_counter1);
+                let x1 = U32Target(builder.select(is_updated, updated_counter, input_counter1));
 
                 let key = builder.constant_hash(key);
                 let elements = updated_key
@@ -152,7 +328,7 @@ impl<const S: usize> ClockCircuit<S> {
                     .collect();
                 updated_key = HashOutTarget::from_vec(elements);
 
-                let x2 = U32Target(*input_counter2);
+                let x2 = U32Target(builder.select(targets.is_base, zero, *input_counter2));
                 range_check_u32_circuit(&mut builder, vec![x1, x2]);
                 // max(x1, x2)
                 let le = list_le_u32_circuit(&mut builder, vec![x1], vec![x2]);
@@ -164,116 +340,211 @@ impl<const S: usize> ClockCircuit<S> {
 
         // let msg = builder.biguint_to_nonnative::<Secp256K1Scalar>(&updated_counter);
         // verify_message_circuit(&mut builder, msg, sig, ECDSAPublicKeyTarget(updated_key));
+        // in place of the signature, the updater proves knowledge of the preimage of the updated
+        // index's key, so only the holder of `index_secret(i)` can advance counter i. when merging
+        // no index matches and the dummy key is selected, whose secret is public, which is fine
+        // because then no counter is updated
         let key = builder.hash_n_to_hash_no_pad::<PoseidonHash>(vec![sig]);
         builder.connect_hashes(key, updated_key);
 
         builder.register_public_inputs(&output_counters);
+        builder.register_public_inputs(&verifier_data);
         // builder.print_gate_counts(0);
         Self {
             data: builder.build(),
-            targets: Some(ClockCircuitTargets {
-                proof1,
-                verifier_data1,
-                proof2,
-                verifier_data2,
-                updated_index,
-                updated_counter,
-                sig,
-                // enable2,
-            }),
+            targets,
         }
     }
 
     pub fn with_data(data: CircuitData<F, C, D>, config: CircuitConfig) -> Self {
+        let mut builder = CircuitBuilder::new(config);
         Self {
-            targets: Some(ClockCircuitTargets::new(&data, config)),
+            targets: ClockCircuitTargets::new(&mut builder, &data.common),
             data,
         }
     }
+
+    // the number of counters of the clocks proved by this circuit, fixed at genesis
+    pub fn size(&self) -> usize {
+        num_counters(&self.data.common)
+    }
 }
 
-impl<const S: usize> ClockCircuitTargets<S> {
-    fn new(circuit: &CircuitData<F, C, D>, config: CircuitConfig) -> Self {
-        let mut builder = CircuitBuilder::new(config);
-        // let num_limbs = CircuitBuilder::<F, D>::num_nonnative_limbs::<Secp256K1Scalar>();
+// the verifier half of a `ClockCircuit`, for observers that verify and compare clocks but never
+// update them, so they don't need to hold the prover data
+#[derive(Debug)]
+pub struct ClockVerifier {
+    pub data: VerifierCircuitData<F, C, D>,
+}
+
+impl ClockCircuit {
+    pub fn verifier(&self) -> ClockVerifier {
+        ClockVerifier {
+            data: self.data.verifier_data(),
+        }
+    }
+}
+
+impl ClockVerifier {
+    pub fn size(&self) -> usize {
+        num_counters(&self.data.common)
+    }
+
+    pub fn verify(&self, clock: &Clock) -> anyhow::Result<()> {
+        clock.check_verifier_data(&self.data.verifier_only)?;
+        self.data.verify(clock.proof.clone()).map_err(Into::into)
+    }
+}
+
+impl ClockCircuitTargets {
+    // the target indices are assigned in allocation order, so this is the only place allowed to
+    // allocate them
+    fn new(builder: &mut CircuitBuilder<F, D>, inner_common: &CommonCircuitData<F, D>) -> Self {
+        let cap_height = inner_common.config.fri_config.cap_height;
         Self {
-            proof1: builder.add_virtual_proof_with_pis(&circuit.common),
-            proof2: builder.add_virtual_proof_with_pis(&circuit.common),
+            proof1: builder.add_virtual_proof_with_pis(inner_common),
+            proof2: builder.add_virtual_proof_with_pis(inner_common),
+            verifier_data: builder.add_virtual_verifier_data(cap_height),
+            // constrained by `ClockCircuit::new`
+            is_base: builder.add_virtual_bool_target_unsafe(),
             updated_index: builder.add_virtual_target(),
             updated_counter: builder.add_virtual_target(),
+            // although there is `add_virtual_nonnative_target`, but seems like currently it can
+            // only be internally used by generators. there's no `set_nonnative_target` companion
+            // for witness
+            // let num_limbs = CircuitBuilder::<F, D>::num_nonnative_limbs::<Secp256K1Scalar>();
             sig: builder.add_virtual_target(),
-            verifier_data1: builder
-                .add_virtual_verifier_data(circuit.common.config.fri_config.cap_height),
-            verifier_data2: builder
-                .add_virtual_verifier_data(circuit.common.config.fri_config.cap_height),
         }
     }
 }
 
-const DUMMY_SECRET: F = F::NEG_ONE;
+// a circuit of the same shape as the clock circuit that proves nothing, so its proofs can stand in
+// for the inner proofs when proving genesis. plonky2's `dummy_circuit` does the same but refuses
+// zero knowledge configs, because the blinding gates throw off its gate count. here the count only
+// has to land within the same power of two
+struct BaseCircuit {
+    data: CircuitData<F, C, D>,
+    public_inputs: Vec<Target>,
+}
 
-impl<const S: usize> Clock<S> {
-    pub fn genesis(
-        keys: [HashOut<F>; S],
-        config: CircuitConfig,
-    ) -> anyhow::Result<(Self, ClockCircuit<S>)> {
-        let mut circuit = ClockCircuit::new_genesis(config.clone());
-        let mut timing =
-            TimingTree::new("prove genesis", "INFO".parse().map_err(anyhow::Error::msg)?);
-        let proof = prove(
-            &circuit.data.prover_only,
-            &circuit.data.common,
-            PartialWitness::new(),
-            &mut timing,
-        )?;
+impl BaseCircuit {
+    fn new(common: &CommonCircuitData<F, D>) -> anyhow::Result<Self> {
+        let mut builder = CircuitBuilder::<F, D>::new(common.config.clone());
+        for _ in 0..common.degree() / 2 + 1 {
+            builder.add_gate(NoopGate, vec![]);
+        }
+        for gate in &common.gates {
+            builder.add_gate_to_gate_set(gate.clone());
+        }
+        let public_inputs = (0..common.num_public_inputs)
+            .map(|_| builder.add_virtual_public_input())
+            .collect();
+        let data = builder.build::<C>();
+        anyhow::ensure!(
+            data.common == *common,
+            "base circuit does not match the clock circuit"
+        );
+        Ok(Self {
+            data,
+            public_inputs,
+        })
+    }
+
+    fn prove(&self) -> anyhow::Result<ProofWithPublicInputs<F, C, D>> {
+        let mut pw = PartialWitness::new();
+        for target in &self.public_inputs {
+            pw.set_target(*target, F::ZERO)
+        }
+        let mut timing = TimingTree::new("prove base", "INFO".parse().map_err(anyhow::Error::msg)?);
+        let proof = prove(&self.data.prover_only, &self.data.common, pw, &mut timing)?;
         timing.print();
-        let mut clock = Self {
-            proof,
-            // depth: 0
-        };
+        Ok(proof)
+    }
+}
 
-        let dummy_key = public_key(DUMMY_SECRET);
-        let mut inner_circuit = circuit;
-        for _ in 0..4 {
-            circuit = ClockCircuit::new(&inner_circuit, &keys, dummy_key, config.clone());
-            clock = clock.merge_internal(&clock, &circuit, &inner_circuit)?;
-            inner_circuit = circuit;
+const DUMMY_SECRET: F = F::NEG_ONE;
+
+// the counter that a proof sets, together with the secret of the index's key
+#[derive(Debug, Clone, Copy)]
+struct Increment {
+    index: usize,
+    counter: u32,
+    secret: F,
+}
+
+impl Increment {
+    // an index out of bound, so no counter is set, and the dummy key signs for it
+    fn merge(size: usize) -> Self {
+        Self {
+            index: size + 1,
+            counter: u32::MAX,
+            secret: DUMMY_SECRET,
         }
+    }
+}
 
+impl Clock {
+    pub fn genesis(
+        keys: &[HashOut<F>],
+        config: CircuitConfig,
+    ) -> anyhow::Result<(Self, ClockCircuit)> {
+        let dummy_key = public_key(DUMMY_SECRET);
+        let (circuit, base) = ClockCircuit::build(keys, dummy_key, config)?;
+        let base = Self {
+            proof: base.prove()?,
+        };
+        let clock = base.prove_internal(
+            &base,
+            &circuit,
+            &circuit.data.verifier_only,
+            true,
+            Increment::merge(circuit.size()),
+            "prove genesis",
+        )?;
         assert!(clock.counters().all(|counter| counter == 0));
-        Ok((clock, inner_circuit))
+        Ok((clock, circuit))
     }
 
     pub fn with_proof_and_circuit(
         proof: ProofWithPublicInputs<F, C, D>,
         data: CircuitData<F, C, D>,
         config: CircuitConfig,
-    ) -> (Self, ClockCircuit<S>) {
+    ) -> (Self, ClockCircuit) {
         (Self { proof }, ClockCircuit::with_data(data, config))
     }
 
-    fn merge_internal(
+    // `verifier_only` is what the proof exposes as the verifier data of the clock circuit, which
+    // is always the circuit's own unless testing against a malicious prover
+    fn prove_internal(
         &self,
         other: &Self,
-        circuit: &ClockCircuit<S>,
-        inner_circuit: &ClockCircuit<S>,
+        circuit: &ClockCircuit,
+        verifier_only: &VerifierOnlyCircuitData<C, D>,
+        is_base: bool,
+        increment: Increment,
+        name: &str,
     ) -> anyhow::Result<Self> {
         let clock1 = self;
         let clock2 = other;
         let mut pw = PartialWitness::new();
-        let targets = circuit.targets.as_ref().unwrap();
+        let targets = &circuit.targets;
         pw.set_proof_with_pis_target(&targets.proof1, &clock1.proof);
-        pw.set_verifier_data_target(&targets.verifier_data1, &inner_circuit.data.verifier_only);
         pw.set_proof_with_pis_target(&targets.proof2, &clock2.proof);
-        pw.set_verifier_data_target(&targets.verifier_data2, &inner_circuit.data.verifier_only);
-        pw.set_target(targets.updated_index, F::from_canonical_usize(S + 1));
-        pw.set_target(targets.updated_counter, F::from_canonical_u32(u32::MAX));
+        pw.set_verifier_data_target(&targets.verifier_data, verifier_only);
+        pw.set_bool_target(targets.is_base, is_base);
+        pw.set_target(
+            targets.updated_index,
+            F::from_canonical_usize(increment.index),
+        );
+        pw.set_target(
+            targets.updated_counter,
+            F::from_canonical_u32(increment.counter),
+        );
         // let msg = Secp256K1Scalar::from_canonical_u32(u32::MAX);
         // let sig = sign_message(msg, DUMMY_SECRET);
-        pw.set_target(targets.sig, DUMMY_SECRET);
+        pw.set_target(targets.sig, increment.secret);
 
-        let mut timing =
-            TimingTree::new("prove merge", "INFO".parse().map_err(anyhow::Error::msg)?);
+        let mut timing = TimingTree::new(name, "INFO".parse().map_err(anyhow::Error::msg)?);
         let proof = prove(
             &circuit.data.prover_only,
             &circuit.data.common,
@@ -281,19 +552,10 @@ impl<const S: usize> Clock<S> {
             &mut timing,
         )?;
         timing.print();
-
-        let clock = Self {
+        Ok(Self {
             proof,
             // depth: self.depth.max(other.depth),
-        };
-        assert!(clock
-            .counters()
-            .zip(clock1.counters())
-            .zip(clock2.counters())
-            .all(|((output_counter, input_counter1), input_counter2)| {
-                output_counter == input_counter1.max(input_counter2)
-            }));
-        Ok(clock)
+        })
     }
 
     pub fn update(
@@ -301,7 +563,7 @@ impl<const S: usize> Clock<S> {
         index: usize,
         secret: F,
         other: &Self,
-        circuit: &ClockCircuit<S>,
+        circuit: &ClockCircuit,
     ) -> anyhow::Result<Self> {
         let counter = self
             .counters()
@@ -316,33 +578,18 @@ impl<const S: usize> Clock<S> {
             + 1;
         let clock1 = self;
         let clock2 = other;
-        let inner_circuit = circuit;
-        let mut pw = PartialWitness::new();
-        let targets = circuit.targets.as_ref().unwrap();
-        pw.set_proof_with_pis_target(&targets.proof1, &clock1.proof);
-        pw.set_verifier_data_target(&targets.verifier_data1, &inner_circuit.data.verifier_only);
-        pw.set_proof_with_pis_target(&targets.proof2, &clock2.proof);
-        pw.set_verifier_data_target(&targets.verifier_data2, &inner_circuit.data.verifier_only);
-        pw.set_target(targets.updated_index, F::from_canonical_usize(index));
-        pw.set_target(targets.updated_counter, F::from_canonical_u32(counter));
-        // let msg = Secp256K1Scalar::from_canonical_u32(u32::MAX);
-        // let sig = sign_message(msg, DUMMY_SECRET);
-        pw.set_target(targets.sig, secret);
-
-        let mut timing =
-            TimingTree::new("prove update", "INFO".parse().map_err(anyhow::Error::msg)?);
-        let proof = prove(
-            &circuit.data.prover_only,
-            &circuit.data.common,
-            pw,
-            &mut timing,
+        let clock = clock1.prove_internal(
+            clock2,
+            circuit,
+            &circuit.data.verifier_only,
+            false,
+            Increment {
+                index,
+                counter,
+                secret,
+            },
+            "prove update",
         )?;
-        timing.print();
-
-        let clock = Self {
-            proof,
-            // depth: self.depth.max(other.depth),
-        };
         assert!(clock
             .counters()
             .enumerate()
@@ -358,9 +605,60 @@ impl<const S: usize> Clock<S> {
         Ok(clock)
     }
 
-    pub fn verify(&self, circuit: &ClockCircuit<S>) -> anyhow::Result<()> {
+    pub fn merge(&self, other: &Self, circuit: &ClockCircuit) -> anyhow::Result<Self> {
+        let clock1 = self;
+        let clock2 = other;
+        let clock = clock1.prove_internal(
+            clock2,
+            circuit,
+            &circuit.data.verifier_only,
+            false,
+            Increment::merge(circuit.size()),
+            "prove merge",
+        )?;
+        assert!(clock
+            .counters()
+            .zip(clock1.counters())
+            .zip(clock2.counters())
+            .all(|((output_counter, input_counter1), input_counter2)| {
+                output_counter == input_counter1.max(input_counter2)
+            }));
+        Ok(clock)
+    }
+
+    // merge pairwise in a balanced tree, one merge at a time. n clocks take n - 1 merge proofs,
+    // as there is no circuit that merges more than two clocks at once. the merges are not proved
+    // concurrently, because a single proof already occupies the whole thread pool with plonky2's
+    // own parallelism, and concurrent proofs would only multiply the peak memory of the prover. an
+    // odd clock out of a round is carried to the next one
+    pub fn merge_all(clocks: &[Self], circuit: &ClockCircuit) -> anyhow::Result<Self> {
+        anyhow::ensure!(!clocks.is_empty(), "no clock to merge");
+        let mut clocks = clocks.to_vec();
+        while clocks.len() > 1 {
+            clocks = clocks
+                .chunks(2)
+                .map(|chunk| match chunk {
+                    [clock1, clock2] => clock1.merge(clock2, circuit),
+                    [clock] => Ok(clock.clone()),
+                    _ => unreachable!(),
+                })
+                .collect::<anyhow::Result<_>>()?;
+        }
+        Ok(clocks.pop().unwrap())
+    }
+
+    pub fn verify(&self, circuit: &ClockCircuit) -> anyhow::Result<()> {
+        self.check_verifier_data(&circuit.data.verifier_only)?;
         circuit.data.verify(self.proof.clone()).map_err(Into::into)
     }
+
+    // plonky2 has no aggregated verification, so every clock is still verified on its own against
+    // the shared verifier data. the parallel loop only reduces the wall-clock time, not the work
+    pub fn verify_batch(clocks: &[Self], circuit: &ClockCircuit) -> anyhow::Result<()> {
+        clocks
+            .par_iter()
+            .try_for_each(|clock| clock.verify(circuit))
+    }
 }
 
 pub fn index_secret(index: usize) -> F {
@@ -378,22 +676,17 @@ mod tests {
     use super::*;
 
     const S: usize = 4;
-    fn genesis_and_circuit() -> (Clock<S>, ClockCircuit<S>) {
-        Clock::<S>::genesis(
-            [(); S].map({
-                let mut i = 0;
-                move |()| {
-                    let secret = index_secret(i);
-                    i += 1;
-                    public_key(secret)
-                }
-            }),
+    fn genesis_and_circuit() -> (Clock, ClockCircuit) {
+        Clock::genesis(
+            &(0..S)
+                .map(|i| public_key(index_secret(i)))
+                .collect::<Vec<_>>(),
             CircuitConfig::standard_ecc_config(),
         )
         .unwrap()
     }
 
-    static GENESIS_AND_CIRCUIT: OnceLock<(Clock<S>, ClockCircuit<S>)> = OnceLock::new();
+    static GENESIS_AND_CIRCUIT: OnceLock<(Clock, ClockCircuit)> = OnceLock::new();
 
     #[test]
     #[should_panic]
@@ -404,6 +697,59 @@ mod tests {
             .unwrap();
     }
 
+    // a clock circuit built the same way but with keys of which the attacker holds every secret
+    fn forged_genesis_and_circuit() -> (Clock, ClockCircuit) {
+        Clock::genesis(
+            &(0..S)
+                .map(|i| public_key(index_secret(S + i)))
+                .collect::<Vec<_>>(),
+            CircuitConfig::standard_ecc_config(),
+        )
+        .unwrap()
+    }
+
+    static FORGED_GENESIS_AND_CIRCUIT: OnceLock<(Clock, ClockCircuit)> = OnceLock::new();
+
+    #[test]
+    #[should_panic]
+    fn forged_inner_circuit() {
+        let (genesis, circuit) = GENESIS_AND_CIRCUIT.get_or_init(genesis_and_circuit);
+        let (forged_genesis, forged_circuit) =
+            FORGED_GENESIS_AND_CIRCUIT.get_or_init(forged_genesis_and_circuit);
+        let forged = forged_genesis.update(0, index_secret(S), forged_genesis, forged_circuit);
+        let Ok(forged) = forged else {
+            return; // to trigger `should_panic` failure
+        };
+        genesis
+            .merge(&forged, circuit)
+            .unwrap()
+            .verify(circuit)
+            .unwrap();
+    }
+
+    #[test]
+    fn forged_verifier_data() -> anyhow::Result<()> {
+        let (_, circuit) = GENESIS_AND_CIRCUIT.get_or_init(genesis_and_circuit);
+        let (forged_genesis, forged_circuit) =
+            FORGED_GENESIS_AND_CIRCUIT.get_or_init(forged_genesis_and_circuit);
+        let forged = forged_genesis.update(0, index_secret(S), forged_genesis, forged_circuit)?;
+        // a malicious prover exposes the verifier data of the forged circuit, against which the
+        // forged proofs verify, so the proof is valid for the clock circuit alone
+        let clock = forged.prove_internal(
+            &forged,
+            circuit,
+            &forged_circuit.data.verifier_only,
+            false,
+            Increment::merge(S),
+            "prove forged merge",
+        )?;
+        circuit.data.verify(clock.proof.clone())?;
+        assert_eq!(clock.counters().collect::<Vec<_>>(), [1, 0, 0, 0]);
+        assert!(clock.verify(circuit).is_err());
+        assert!(circuit.verifier().verify(&clock).is_err());
+        Ok(())
+    }
+
     #[test]
     #[should_panic]
     fn malformed_counters_recursive() {
@@ -418,4 +764,180 @@ mod tests {
             .clone_from(&genesis.proof.public_inputs);
         clock1.update(0, index_secret(0), &clock1, circuit).unwrap();
     }
+
+    #[test]
+    fn serialization_round_trip() -> anyhow::Result<()> {
+        let (genesis, circuit) = GENESIS_AND_CIRCUIT.get_or_init(genesis_and_circuit);
+        let circuit =
+            ClockCircuit::from_bytes(&circuit.to_bytes()?, CircuitConfig::standard_ecc_config())?;
+        let genesis = Clock::from_bytes(&genesis.to_bytes(), &circuit)?;
+        let compressed_bytes = genesis.to_compressed_bytes(&circuit)?;
+        assert!(compressed_bytes.len() < genesis.to_bytes().len());
+        assert_eq!(
+            Clock::from_compressed_bytes(&compressed_bytes, &circuit)?,
+            genesis
+        );
+        let clock = genesis.update(0, index_secret(0), &genesis, &circuit)?;
+        clock.verify(&circuit)?;
+        assert_eq!(clock.counters().collect::<Vec<_>>(), [1, 0, 0, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn other_size() -> anyhow::Result<()> {
+        let (genesis, circuit) = GENESIS_AND_CIRCUIT.get_or_init(genesis_and_circuit);
+        let (genesis3, circuit3) = Clock::genesis(
+            &(0..3)
+                .map(|i| public_key(index_secret(i)))
+                .collect::<Vec<_>>(),
+            CircuitConfig::standard_ecc_config(),
+        )?;
+        assert_eq!(circuit3.size(), 3);
+        assert_eq!(circuit3.verifier().size(), 3);
+        assert_eq!(genesis3.size(), 3);
+        assert_eq!(circuit.size(), S);
+        assert_eq!(genesis.size(), S);
+
+        let clock = genesis3.update(2, index_secret(2), &genesis3, &circuit3)?;
+        clock.verify(&circuit3)?;
+        assert_eq!(clock.counters().collect::<Vec<_>>(), [0, 0, 1]);
+
+        let err = Clock::from_bytes(&genesis.to_bytes(), &circuit3).unwrap_err();
+        assert!(err.to_string().contains("clock size mismatch"));
+        assert!(genesis3 != *genesis);
+        assert_eq!(genesis3.partial_cmp(genesis), None);
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_circuit_bytes() -> anyhow::Result<()> {
+        let (_, circuit) = GENESIS_AND_CIRCUIT.get_or_init(genesis_and_circuit);
+        let mut bytes = circuit.to_bytes()?;
+        let len = bytes.len();
+        bytes[len / 2] ^= 1;
+        assert!(ClockCircuit::from_bytes(&bytes, CircuitConfig::standard_ecc_config()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_all() -> anyhow::Result<()> {
+        let (genesis, circuit) = GENESIS_AND_CIRCUIT.get_or_init(genesis_and_circuit);
+        let clocks = (0..3)
+            .map(|i| genesis.update(i, index_secret(i), genesis, circuit))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Clock::verify_batch(&clocks, circuit)?;
+        let clock = Clock::merge_all(&clocks, circuit)?;
+        clock.verify(circuit)?;
+        assert_eq!(clock.counters().collect::<Vec<_>>(), [1, 1, 1, 0]);
+
+        // 5 clocks take rounds of 5, 3 and 2 clocks, so one clock is carried through the middle
+        // round as well
+        let inputs = [&clocks[..], &[genesis.clone(), clocks[0].clone()][..]].concat();
+        let clock = Clock::merge_all(&inputs, circuit)?;
+        clock.verify(circuit)?;
+        assert_eq!(clock.counters().collect::<Vec<_>>(), [1, 1, 1, 0]);
+
+        let clock = Clock::merge_all(&clocks[..1], circuit)?;
+        assert_eq!(clock.to_bytes(), clocks[0].to_bytes());
+        assert!(Clock::merge_all(&[], circuit).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_batch() -> anyhow::Result<()> {
+        let (genesis, circuit) = GENESIS_AND_CIRCUIT.get_or_init(genesis_and_circuit);
+        let clock = genesis.update(0, index_secret(0), genesis, circuit)?;
+        let mut tampered = clock.clone();
+        tampered.proof.public_inputs[1] = F::ONE;
+        Clock::verify_batch(&[genesis.clone(), clock.clone()], circuit)?;
+        assert!(Clock::verify_batch(&[genesis.clone(), tampered, clock], circuit).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn partial_order() -> anyhow::Result<()> {
+        let (genesis, circuit) = GENESIS_AND_CIRCUIT.get_or_init(genesis_and_circuit);
+        let clock1 = genesis.update(0, index_secret(0), genesis, circuit)?;
+        let clock2 = genesis.update(1, index_secret(1), genesis, circuit)?;
+        assert!(*genesis < clock1);
+        assert!(*genesis < clock2);
+        assert_eq!(clock1.partial_cmp(&clock2), None);
+        let clock3 = clock1.merge(&clock2, circuit)?;
+        assert!(clock1 < clock3 && clock2 < clock3);
+
+        assert_eq!(genesis.arbitrary_cmp(&clock1), Ordering::Less);
+        assert_eq!(clock1.arbitrary_cmp(&clock2), Ordering::Greater);
+        assert_eq!(clock2.arbitrary_cmp(&clock1), Ordering::Less);
+        assert_eq!(clock3.arbitrary_cmp(&clock1), Ordering::Greater);
+        assert_eq!(clock3.arbitrary_cmp(&clock3), Ordering::Equal);
+        Ok(())
+    }
+
+    #[test]
+    fn verifier_round_trip() -> anyhow::Result<()> {
+        let (genesis, circuit) = GENESIS_AND_CIRCUIT.get_or_init(genesis_and_circuit);
+        let verifier = ClockVerifier::from_bytes(&circuit.verifier().to_bytes()?)?;
+        let clock = genesis.update(0, index_secret(0), genesis, circuit)?;
+        let clock = verifier.clock_from_compressed_bytes(&clock.to_compressed_bytes(circuit)?)?;
+        assert!(*genesis < clock);
+        verifier.verify(genesis)?;
+        Ok(())
+    }
+
+    #[test]
+    fn shrink() -> anyhow::Result<()> {
+        let (genesis, circuit) = GENESIS_AND_CIRCUIT.get_or_init(genesis_and_circuit);
+        let shrink_circuit = shrink::ShrinkCircuit::new(&circuit.verifier());
+        let clock = genesis.update(0, index_secret(0), genesis, circuit)?;
+        let shrunk = shrink_circuit.prove(&clock)?;
+        shrink_circuit.verify(&shrunk)?;
+        let bytes = shrunk.to_bytes();
+        assert!(bytes.len() < clock.to_compressed_bytes(circuit)?.len());
+
+        let verifier = shrink::ShrinkVerifier::from_bytes(&shrink_circuit.verifier().to_bytes()?)?;
+        let shrunk = shrink::ShrunkClock::from_bytes(&bytes, &verifier)?;
+        assert_eq!(shrunk.counters().collect::<Vec<_>>(), [1, 0, 0, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn compare() -> anyhow::Result<()> {
+        let (genesis, circuit) = GENESIS_AND_CIRCUIT.get_or_init(genesis_and_circuit);
+        let mut config = CircuitConfig::standard_ecc_config();
+        config.zero_knowledge = true;
+        let compare_circuit = compare::CompareCircuit::new(&circuit.verifier(), config);
+        let clock1 = genesis.update(0, index_secret(0), genesis, circuit)?;
+        let clock2 = genesis.update(1, index_secret(1), genesis, circuit)?;
+        let blinding1 = [F::from_canonical_u64(1); 4];
+        let blinding2 = [F::from_canonical_u64(2); 4];
+
+        let comparison = compare_circuit.prove(genesis, blinding1, &clock1, blinding2)?;
+        compare_circuit.verify(&comparison)?;
+        assert_eq!(comparison.ordering()?, Some(Ordering::Less));
+        assert_eq!(
+            comparison.commitments()?,
+            (genesis.commitment(blinding1), clock1.commitment(blinding2))
+        );
+
+        let comparison = compare_circuit.prove(&clock1, blinding1, &clock2, blinding2)?;
+        compare_circuit.verify(&comparison)?;
+        assert_eq!(comparison.ordering()?, None);
+
+        let verifier =
+            compare::CompareVerifier::from_bytes(&compare_circuit.verifier().to_bytes()?)?;
+        let comparison = compare::Comparison::from_bytes(&comparison.to_bytes(), &verifier)?;
+        assert_eq!(comparison.ordering()?, None);
+        for i in [8, 9] {
+            let mut tampered = comparison.clone();
+            tampered.proof.public_inputs[i] = F::ONE - tampered.proof.public_inputs[i];
+            assert!(tampered.ordering()?.is_some());
+            assert!(verifier.verify(&tampered).is_err());
+            assert!(compare::Comparison::from_bytes(&tampered.to_bytes(), &verifier).is_err());
+        }
+
+        let mut truncated = comparison.clone();
+        truncated.proof.public_inputs.truncate(8);
+        assert!(truncated.ordering().is_err());
+        Ok(())
+    }
 }
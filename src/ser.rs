@@ -1,80 +1,88 @@
 use plonky2::field::extension::Extendable;
+use plonky2::field::types::{Field as _, PrimeField64 as _};
 // use plonky2::field::secp256k1_base::Secp256K1Base;
 // use plonky2::field::secp256k1_scalar::Secp256K1Scalar;
 use plonky2::hash::hash_types::RichField;
+use plonky2::hash::hashing::hash_n_to_hash_no_pad;
+use plonky2::hash::poseidon::PoseidonPermutation;
 use plonky2::iop::generator::SimpleGenerator as _;
 use plonky2::iop::generator::WitnessGeneratorRef;
 use plonky2::util::serialization::IoError;
 use plonky2::util::serialization::Read;
 use plonky2::util::serialization::Write;
 
-// use plonky2::plonk::circuit_data::CircuitConfig;
-// use plonky2::plonk::circuit_data::CircuitData;
-// use plonky2::plonk::config::PoseidonGoldilocksConfig;
-// use plonky2::plonk::proof::ProofWithPublicInputs;
-
-// use plonky2::gates::arithmetic_base::ArithmeticGate;
-// use plonky2::gates::arithmetic_extension::ArithmeticExtensionGate;
-// use plonky2::gates::base_sum::BaseSumGate;
-// use plonky2::gates::constant::ConstantGate;
-// use plonky2::gates::coset_interpolation::CosetInterpolationGate;
-// use plonky2::gates::exponentiation::ExponentiationGate;
-// use plonky2::gates::lookup::LookupGate;
-// use plonky2::gates::lookup_table::LookupTableGate;
-// use plonky2::gates::multiplication_extension::MulExtensionGate;
-// use plonky2::gates::noop::NoopGate;
-// use plonky2::gates::poseidon::PoseidonGate;
-// use plonky2::gates::poseidon_mds::PoseidonMdsGate;
-// use plonky2::gates::public_input::PublicInputGate;
-// use plonky2::gates::random_access::RandomAccessGate;
-// use plonky2::gates::reducing::ReducingGate;
-// use plonky2::gates::reducing_extension::ReducingExtensionGate;
-// use plonky2::get_gate_tag_impl;
-// use plonky2::impl_gate_serializer;
-// use plonky2::read_gate_impl;
-// use plonky2::util::serialization::GateSerializer;
-// use plonky2_u32::gates::add_many_u32::U32AddManyGate;
-// use plonky2_u32::gates::arithmetic_u32::U32ArithmeticGate;
-// use plonky2_u32::gates::comparison::ComparisonGate;
-// use plonky2_u32::gates::range_check_u32::U32RangeCheckGate;
-// use plonky2_u32::gates::subtraction_u32::U32SubtractionGate;
-
-// disable for now because requires `log` crate for macro expansion
-// and i don't need (de)serialize now
-// #[derive(Debug)]
-// pub struct DefaultGateSerializer;
-// impl<F: RichField + Extendable<D>, const D: usize> GateSerializer<F, D> for DefaultGateSerializer {
-//     impl_gate_serializer! {
-//         DefaultGateSerializer,
-//         ArithmeticGate,
-//         ArithmeticExtensionGate<D>,
-//         BaseSumGate<2>,
-//         BaseSumGate<4>,
-//         ComparisonGate<F, D>,
-//         ConstantGate,
-//         CosetInterpolationGate<F, D>,
-//         ExponentiationGate<F, D>,
-//         LookupGate,
-//         LookupTableGate,
-//         MulExtensionGate<D>,
-//         NoopGate,
-//         PoseidonMdsGate<F, D>,
-//         PoseidonGate<F, D>,
-//         PublicInputGate,
-//         RandomAccessGate<F, D>,
-//         ReducingExtensionGate<D>,
-//         ReducingGate<D>,
-//         U32AddManyGate<F, D>,
-//         U32ArithmeticGate<F, D>,
-//         U32RangeCheckGate<F, D>,
-//         U32SubtractionGate<F, D>
-//     }
-// }
+use plonky2::plonk::circuit_data::CircuitConfig;
+use plonky2::plonk::circuit_data::CircuitData;
+use plonky2::plonk::circuit_data::CommonCircuitData;
+use plonky2::plonk::circuit_data::VerifierCircuitData;
+use plonky2::plonk::circuit_data::VerifierOnlyCircuitData;
+use plonky2::plonk::proof::{CompressedProofWithPublicInputs, ProofWithPublicInputs};
+
+use plonky2::gates::arithmetic_base::ArithmeticGate;
+use plonky2::gates::arithmetic_extension::ArithmeticExtensionGate;
+use plonky2::gates::base_sum::BaseSumGate;
+use plonky2::gates::constant::ConstantGate;
+use plonky2::gates::coset_interpolation::CosetInterpolationGate;
+use plonky2::gates::exponentiation::ExponentiationGate;
+use plonky2::gates::lookup::LookupGate;
+use plonky2::gates::lookup_table::LookupTableGate;
+use plonky2::gates::multiplication_extension::MulExtensionGate;
+use plonky2::gates::noop::NoopGate;
+use plonky2::gates::poseidon::PoseidonGate;
+use plonky2::gates::poseidon_mds::PoseidonMdsGate;
+use plonky2::gates::public_input::PublicInputGate;
+use plonky2::gates::random_access::RandomAccessGate;
+use plonky2::gates::reducing::ReducingGate;
+use plonky2::gates::reducing_extension::ReducingExtensionGate;
+use plonky2::get_gate_tag_impl;
+use plonky2::impl_gate_serializer;
+use plonky2::read_gate_impl;
+use plonky2::util::serialization::GateSerializer;
+use plonky2_u32::gates::add_many_u32::U32AddManyGate;
+use plonky2_u32::gates::arithmetic_u32::U32ArithmeticGate;
+use plonky2_u32::gates::comparison::ComparisonGate;
+use plonky2_u32::gates::range_check_u32::U32RangeCheckGate;
+use plonky2_u32::gates::subtraction_u32::U32SubtractionGate;
+
+#[derive(Debug)]
+pub struct DefaultGateSerializer;
+impl<F: RichField + Extendable<D>, const D: usize> GateSerializer<F, D> for DefaultGateSerializer {
+    impl_gate_serializer! {
+        DefaultGateSerializer,
+        ArithmeticGate,
+        ArithmeticExtensionGate<D>,
+        BaseSumGate<2>,
+        BaseSumGate<4>,
+        ComparisonGate<F, D>,
+        ConstantGate,
+        CosetInterpolationGate<F, D>,
+        ExponentiationGate<F, D>,
+        LookupGate,
+        LookupTableGate,
+        MulExtensionGate<D>,
+        NoopGate,
+        PoseidonMdsGate<F, D>,
+        PoseidonGate<F, D>,
+        PublicInputGate,
+        RandomAccessGate<F, D>,
+        ReducingExtensionGate<D>,
+        ReducingGate<D>,
+        U32AddManyGate<F, D>,
+        U32ArithmeticGate<F, D>,
+        U32RangeCheckGate<F, D>,
+        U32SubtractionGate<F, D>
+    }
+}
 
 use plonky2::plonk::config::{AlgebraicHasher, GenericConfig};
 use plonky2::util::serialization::WitnessGeneratorSerializer;
 
-// use crate::ClockCircuit;
+use crate::{
+    compare::{CompareVerifier, Comparison, NUM_PUBLIC_INPUTS},
+    num_counters,
+    shrink::{ShrinkVerifier, ShrunkClock},
+    Clock, ClockCircuit, ClockVerifier,
+};
 
 #[derive(Debug, Default)]
 pub struct DefaultGeneratorSerializer<C: GenericConfig<D>, const D: usize> {
@@ -360,36 +368,314 @@ where
     }
 }
 
-// impl<const S: usize> crate::ClockCircuit<S> {
-//     pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
-//         self.data
-//             .to_bytes(
-//                 &DefaultGateSerializer,
-//                 &DefaultGeneratorSerializer::<PoseidonGoldilocksConfig, { crate::D }>::default(),
-//             )
-//             .map_err(anyhow::Error::msg)
-//     }
-// }
-
-impl<const S: usize> crate::Clock<S> {
+// every serialized artifact starts with a fixed size header:
+// magic (4 bytes) | format version (u32 LE) | clock size (u32 LE)
+// circuit and verifier data are additionally followed by a checksum of themselves. a clock needs no
+// checksum because its proof is verified on loading
+const FORMAT_VERSION: u32 = 2;
+const CLOCK_MAGIC: &[u8; 4] = b"CCLK";
+const CIRCUIT_MAGIC: &[u8; 4] = b"CCIR";
+const COMPRESSED_CLOCK_MAGIC: &[u8; 4] = b"CCLZ";
+const VERIFIER_MAGIC: &[u8; 4] = b"CCVD";
+const SHRUNK_CLOCK_MAGIC: &[u8; 4] = b"CCSK";
+const SHRINK_VERIFIER_MAGIC: &[u8; 4] = b"CCSV";
+const COMPARE_VERIFIER_MAGIC: &[u8; 4] = b"CCCV";
+const COMPARISON_MAGIC: &[u8; 4] = b"CCMP";
+const HEADER_LEN: usize = 12;
+const CHECKSUM_LEN: usize = 32;
+
+fn write_header(buf: &mut Vec<u8>, magic: &[u8; 4], size: usize) {
+    buf.extend_from_slice(magic);
+    buf.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    buf.extend_from_slice(&(size as u32).to_le_bytes());
+}
+
+fn read_header<'a>(bytes: &'a [u8], magic: &[u8; 4]) -> anyhow::Result<(usize, &'a [u8])> {
+    anyhow::ensure!(bytes.len() >= HEADER_LEN, "truncated header");
+    let (header, payload) = bytes.split_at(HEADER_LEN);
+    anyhow::ensure!(
+        header[..4] == magic[..],
+        "unexpected magic {:?}",
+        &header[..4]
+    );
+    let version = u32::from_le_bytes(header[4..8].try_into()?);
+    anyhow::ensure!(
+        version == FORMAT_VERSION,
+        "unsupported format version {version}"
+    );
+    let size = u32::from_le_bytes(header[8..12].try_into()?);
+    Ok((size as _, payload))
+}
+
+pub(crate) fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_LEN] {
+    // 7 bytes per element so that every element is canonical, and the length is appended so that
+    // trailing zeros are not ambiguous
+    let mut elements = bytes
+        .chunks(7)
+        .map(|chunk| {
+            let mut limb = [0; 8];
+            limb[..chunk.len()].copy_from_slice(chunk);
+            crate::F::from_canonical_u64(u64::from_le_bytes(limb))
+        })
+        .collect::<Vec<_>>();
+    elements.push(crate::F::from_canonical_usize(bytes.len()));
+    let hash = hash_n_to_hash_no_pad::<_, PoseidonPermutation<_>>(&elements);
+    let mut buf = [0; CHECKSUM_LEN];
+    for (chunk, element) in buf.chunks_mut(8).zip(hash.elements) {
+        chunk.copy_from_slice(&element.to_canonical_u64().to_le_bytes())
+    }
+    buf
+}
+
+fn write_checksummed(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(data);
+    buf.extend_from_slice(&checksum(data));
+}
+
+fn read_checksummed(payload: &[u8]) -> anyhow::Result<&[u8]> {
+    anyhow::ensure!(payload.len() >= CHECKSUM_LEN, "truncated circuit data");
+    let (data, expected_checksum) = payload.split_at(payload.len() - CHECKSUM_LEN);
+    anyhow::ensure!(
+        checksum(data)[..] == *expected_checksum,
+        "circuit data checksum mismatch"
+    );
+    Ok(data)
+}
+
+impl ClockCircuit {
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let data = self
+            .data
+            .to_bytes(
+                &DefaultGateSerializer,
+                &DefaultGeneratorSerializer::<crate::C, { crate::D }>::default(),
+            )
+            .map_err(anyhow::Error::msg)?;
+        let mut buf = Vec::with_capacity(HEADER_LEN + data.len() + CHECKSUM_LEN);
+        write_header(&mut buf, CIRCUIT_MAGIC, self.size());
+        write_checksummed(&mut buf, &data);
+        Ok(buf)
+    }
+
+    pub fn from_bytes(bytes: &[u8], config: CircuitConfig) -> anyhow::Result<Self> {
+        let (size, payload) = read_header(bytes, CIRCUIT_MAGIC)?;
+        let data = CircuitData::from_bytes(
+            read_checksummed(payload)?,
+            &DefaultGateSerializer,
+            &DefaultGeneratorSerializer::<crate::C, { crate::D }>::default(),
+        )
+        .map_err(anyhow::Error::msg)?;
+        anyhow::ensure!(
+            num_counters(&data.common) == size,
+            "circuit has {} counters, expect {size}",
+            num_counters(&data.common)
+        );
+        Ok(Self::with_data(data, config))
+    }
+}
+
+fn verifier_data_to_bytes(
+    data: &VerifierCircuitData<crate::F, crate::C, { crate::D }>,
+    magic: &[u8; 4],
+    size: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let data = data
+        .to_bytes(&DefaultGateSerializer)
+        .map_err(anyhow::Error::msg)?;
+    let mut buf = Vec::with_capacity(HEADER_LEN + data.len() + CHECKSUM_LEN);
+    write_header(&mut buf, magic, size);
+    write_checksummed(&mut buf, &data);
+    Ok(buf)
+}
+
+fn verifier_data_from_bytes(
+    bytes: &[u8],
+    magic: &[u8; 4],
+) -> anyhow::Result<(usize, VerifierCircuitData<crate::F, crate::C, { crate::D }>)> {
+    let (size, payload) = read_header(bytes, magic)?;
+    let data = VerifierCircuitData::from_bytes(
+        read_checksummed(payload)?.to_vec(),
+        &DefaultGateSerializer,
+    )
+    .map_err(anyhow::Error::msg)?;
+    Ok((size, data))
+}
+
+impl ClockVerifier {
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        verifier_data_to_bytes(&self.data, VERIFIER_MAGIC, self.size())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let (size, data) = verifier_data_from_bytes(bytes, VERIFIER_MAGIC)?;
+        anyhow::ensure!(
+            num_counters(&data.common) == size,
+            "circuit has {} counters, expect {size}",
+            num_counters(&data.common)
+        );
+        Ok(Self { data })
+    }
+
+    // the light counterparts of `Clock::from_bytes` and `Clock::from_compressed_bytes`
+    pub fn clock_from_bytes(&self, bytes: &[u8]) -> anyhow::Result<Clock> {
+        let clock = Clock::decode(bytes, &self.data.common)?;
+        self.verify(&clock)?;
+        Ok(clock)
+    }
+
+    pub fn clock_from_compressed_bytes(&self, bytes: &[u8]) -> anyhow::Result<Clock> {
+        let clock = Clock::decode_compressed(bytes, &self.data.verifier_only, &self.data.common)?;
+        self.verify(&clock)?;
+        Ok(clock)
+    }
+}
+
+impl Clock {
     pub fn to_bytes(&self) -> Vec<u8> {
-        self.proof.to_bytes()
+        let proof = self.proof.to_bytes();
+        let mut buf = Vec::with_capacity(HEADER_LEN + proof.len());
+        write_header(&mut buf, CLOCK_MAGIC, self.size());
+        buf.extend_from_slice(&proof);
+        buf
     }
 
-    // pub fn from_bytes(
-    //     clock_bytes: Vec<u8>,
-    //     circuit_bytes: &[u8],
-    //     config: CircuitConfig,
-    // ) -> anyhow::Result<(Self, crate::ClockCircuit<S>)> {
-    //     let data = CircuitData::from_bytes(
-    //         circuit_bytes,
-    //         &DefaultGateSerializer,
-    //         &DefaultGeneratorSerializer::<PoseidonGoldilocksConfig, { crate::D }>::default(),
-    //     )
-    //     .map_err(anyhow::Error::msg)?;
-    //     let clock = Self {
-    //         proof: ProofWithPublicInputs::from_bytes(clock_bytes, &data.common)?,
-    //     };
-    //     Ok((clock, ClockCircuit::with_data(data, config)))
-    // }
+    pub fn from_bytes(bytes: &[u8], circuit: &ClockCircuit) -> anyhow::Result<Self> {
+        let clock = Self::decode(bytes, &circuit.data.common)?;
+        clock.verify(circuit)?;
+        Ok(clock)
+    }
+
+    // the compressed form deduplicates the FRI query paths, which is most of a proof, so it is the
+    // one to put on the wire. compressing and decompressing requires the circuit. observers that
+    // never update the clock can be sent a `ShrunkClock` instead, which is smaller still
+    pub fn to_compressed_bytes(&self, circuit: &ClockCircuit) -> anyhow::Result<Vec<u8>> {
+        let proof = circuit.data.compress(self.proof.clone())?.to_bytes();
+        let mut buf = Vec::with_capacity(HEADER_LEN + proof.len());
+        write_header(&mut buf, COMPRESSED_CLOCK_MAGIC, circuit.size());
+        buf.extend_from_slice(&proof);
+        Ok(buf)
+    }
+
+    pub fn from_compressed_bytes(bytes: &[u8], circuit: &ClockCircuit) -> anyhow::Result<Self> {
+        let clock =
+            Self::decode_compressed(bytes, &circuit.data.verifier_only, &circuit.data.common)?;
+        clock.verify(circuit)?;
+        Ok(clock)
+    }
+
+    fn decode(
+        bytes: &[u8],
+        common: &CommonCircuitData<crate::F, { crate::D }>,
+    ) -> anyhow::Result<Self> {
+        let (size, payload) = read_header(bytes, CLOCK_MAGIC)?;
+        anyhow::ensure!(
+            size == num_counters(common),
+            "clock size mismatch: expect {} got {size}",
+            num_counters(common)
+        );
+        Ok(Self {
+            proof: ProofWithPublicInputs::from_bytes(payload.to_vec(), common)?,
+        })
+    }
+
+    fn decode_compressed(
+        bytes: &[u8],
+        verifier_only: &VerifierOnlyCircuitData<crate::C, { crate::D }>,
+        common: &CommonCircuitData<crate::F, { crate::D }>,
+    ) -> anyhow::Result<Self> {
+        let (size, payload) = read_header(bytes, COMPRESSED_CLOCK_MAGIC)?;
+        anyhow::ensure!(
+            size == num_counters(common),
+            "clock size mismatch: expect {} got {size}",
+            num_counters(common)
+        );
+        let proof = CompressedProofWithPublicInputs::from_bytes(payload.to_vec(), common)?;
+        Ok(Self {
+            proof: proof.decompress(&verifier_only.circuit_digest, common)?,
+        })
+    }
+}
+
+impl ShrinkVerifier {
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        verifier_data_to_bytes(&self.data, SHRINK_VERIFIER_MAGIC, self.size())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let (size, data) = verifier_data_from_bytes(bytes, SHRINK_VERIFIER_MAGIC)?;
+        anyhow::ensure!(
+            data.common.num_public_inputs == size,
+            "circuit has {} counters, expect {size}",
+            data.common.num_public_inputs
+        );
+        Ok(Self { data })
+    }
+}
+
+impl ShrunkClock {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let proof = self.proof.to_bytes();
+        let mut buf = Vec::with_capacity(HEADER_LEN + proof.len());
+        write_header(&mut buf, SHRUNK_CLOCK_MAGIC, self.proof.public_inputs.len());
+        buf.extend_from_slice(&proof);
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8], verifier: &ShrinkVerifier) -> anyhow::Result<Self> {
+        let (size, payload) = read_header(bytes, SHRUNK_CLOCK_MAGIC)?;
+        anyhow::ensure!(
+            size == verifier.size(),
+            "clock size mismatch: expect {} got {size}",
+            verifier.size()
+        );
+        let clock = Self {
+            proof: ProofWithPublicInputs::from_bytes(payload.to_vec(), &verifier.data.common)?,
+        };
+        verifier.verify(&clock)?;
+        Ok(clock)
+    }
+}
+
+// the size field of the header is the number of public inputs for comparisons, which is fixed
+impl CompareVerifier {
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        verifier_data_to_bytes(
+            &self.data,
+            COMPARE_VERIFIER_MAGIC,
+            self.data.common.num_public_inputs,
+        )
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let (size, data) = verifier_data_from_bytes(bytes, COMPARE_VERIFIER_MAGIC)?;
+        anyhow::ensure!(
+            size == NUM_PUBLIC_INPUTS && data.common.num_public_inputs == NUM_PUBLIC_INPUTS,
+            "expect {NUM_PUBLIC_INPUTS} public inputs, got {size} and {}",
+            data.common.num_public_inputs
+        );
+        Ok(Self { data })
+    }
+}
+
+impl Comparison {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let proof = self.proof.to_bytes();
+        let mut buf = Vec::with_capacity(HEADER_LEN + proof.len());
+        write_header(&mut buf, COMPARISON_MAGIC, self.proof.public_inputs.len());
+        buf.extend_from_slice(&proof);
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8], verifier: &CompareVerifier) -> anyhow::Result<Self> {
+        let (size, payload) = read_header(bytes, COMPARISON_MAGIC)?;
+        anyhow::ensure!(
+            size == NUM_PUBLIC_INPUTS,
+            "expect {NUM_PUBLIC_INPUTS} public inputs, got {size}"
+        );
+        let comparison = Self {
+            proof: ProofWithPublicInputs::from_bytes(payload.to_vec(), &verifier.data.common)?,
+        };
+        verifier.verify(&comparison)?;
+        Ok(comparison)
+    }
 }
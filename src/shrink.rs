@@ -0,0 +1,112 @@
+use plonky2::{
+    field::types::PrimeField64,
+    iop::witness::{PartialWitness, WitnessWrite},
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData, VerifierCircuitData},
+        proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget},
+        prover::prove,
+    },
+    util::timing::TimingTree,
+};
+
+use crate::{verifier_data_targets, Clock, ClockVerifier, C, D, F};
+
+// wraps a clock proof into a smaller one for observers that only verify and read the clock. the
+// clock proof is verified by a circuit with a higher FRI rate and fewer queries, which makes the
+// proof smaller for the same security at the cost of proving time. a shrunk clock cannot be
+// updated or merged anymore, because the clock circuit only verifies clock proofs. the circuit
+// only needs the verifier half of the clock circuit, and observers only need `ShrinkVerifier`
+#[derive(Debug)]
+pub struct ShrinkCircuit {
+    pub data: CircuitData<F, C, D>,
+    proof: ProofWithPublicInputsTarget<D>,
+}
+
+#[derive(Debug)]
+pub struct ShrinkVerifier {
+    pub data: VerifierCircuitData<F, C, D>,
+}
+
+// the public inputs are the counters of the wrapped clock
+#[derive(Debug, Clone)]
+pub struct ShrunkClock {
+    pub proof: ProofWithPublicInputs<F, C, D>,
+}
+
+impl ShrinkCircuit {
+    // rate bits 7 with 12 queries in place of the standard 3 with 28, which is the same 100 bits of
+    // security together with the 16 proof of work bits
+    pub fn config(config: &CircuitConfig) -> CircuitConfig {
+        let mut config = config.clone();
+        config.fri_config.rate_bits = 7;
+        config.fri_config.num_query_rounds = 12;
+        config
+    }
+
+    pub fn new(verifier: &ClockVerifier) -> Self {
+        let common = &verifier.data.common;
+        let mut builder = CircuitBuilder::<F, D>::new(Self::config(&common.config));
+        let proof = builder.add_virtual_proof_with_pis(common);
+        let verifier_data = builder.constant_verifier_data(&verifier.data.verifier_only);
+        builder.verify_proof::<C>(&proof, &verifier_data, common);
+
+        // the exposed verifier data is checked as `Clock::verify` does, and only the counters are
+        // exposed again
+        let size = verifier.size();
+        for (target, verifier_target) in proof.public_inputs[size..]
+            .iter()
+            .zip(verifier_data_targets(&verifier_data))
+        {
+            builder.connect(*target, verifier_target)
+        }
+        builder.register_public_inputs(&proof.public_inputs[..size]);
+        Self {
+            data: builder.build(),
+            proof,
+        }
+    }
+
+    pub fn prove(&self, clock: &Clock) -> anyhow::Result<ShrunkClock> {
+        let mut pw = PartialWitness::new();
+        pw.set_proof_with_pis_target(&self.proof, &clock.proof);
+        let mut timing =
+            TimingTree::new("prove shrink", "INFO".parse().map_err(anyhow::Error::msg)?);
+        let proof = prove(&self.data.prover_only, &self.data.common, pw, &mut timing)?;
+        timing.print();
+        Ok(ShrunkClock { proof })
+    }
+
+    pub fn verify(&self, clock: &ShrunkClock) -> anyhow::Result<()> {
+        self.data.verify(clock.proof.clone()).map_err(Into::into)
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.common.num_public_inputs
+    }
+
+    pub fn verifier(&self) -> ShrinkVerifier {
+        ShrinkVerifier {
+            data: self.data.verifier_data(),
+        }
+    }
+}
+
+impl ShrinkVerifier {
+    pub fn size(&self) -> usize {
+        self.data.common.num_public_inputs
+    }
+
+    pub fn verify(&self, clock: &ShrunkClock) -> anyhow::Result<()> {
+        self.data.verify(clock.proof.clone()).map_err(Into::into)
+    }
+}
+
+impl ShrunkClock {
+    pub fn counters(&self) -> impl Iterator<Item = u32> + '_ {
+        self.proof
+            .public_inputs
+            .iter()
+            .map(|counter| counter.to_canonical_u64() as _)
+    }
+}